@@ -1,5 +1,6 @@
 use lazy_static::lazy_static;
 use std::env;
+use std::ffi::OsString;
 use std::path::Path;
 use std::fs::File;
 use std::io::Read;
@@ -8,6 +9,8 @@ use std::process::Command;
 #[cfg(windows)]
 use std::iter::once;
 
+const TOOLCHAIN_ROOT_ENV_NAME: &str = "ESP_TOOLCHAIN_ROOT";
+const NO_EXEC_ENV_NAME: &str = "ESP_WRAPPER_NO_EXEC";
 const XESPV_VERSIONS: [&str; 2] = ["xespv2p2", "xespv2p1"];
 const XESPV_ARG_PREFIX: &str = "-mespv-spec=";
 const MARCH_ARG_PREFIX: &str = "-march=";
@@ -96,6 +99,54 @@ fn get_short_path_name(long_path: &PathBuf) -> PathBuf {
     get_path_name(long_path, GetShortPathNameW)
 }
 
+/// Collects the toolchain roots to search, in priority order: the install the
+/// wrapper itself lives in, an explicit `ESP_TOOLCHAIN_ROOT` override, then the
+/// parent of every directory on `PATH`. Each root is expected to
+/// hold `bin/` and `lib/` subdirectories.
+fn toolchain_roots(bin_dir: &Path) -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+    if let Some(root) = bin_dir.parent() {
+        roots.push(root.to_path_buf());
+    }
+    if let Some(root) = env::var_os(TOOLCHAIN_ROOT_ENV_NAME) {
+        roots.push(PathBuf::from(root));
+    }
+    if let Some(paths) = env::var_os("PATH") {
+        for entry in env::split_paths(&paths) {
+            if let Some(root) = entry.parent() {
+                roots.push(root.to_path_buf());
+            }
+        }
+    }
+    roots
+}
+
+/// Resolves a toolchain file given its path relative to a root. Returns the
+/// first candidate that exists; on failure prints every searched path.
+fn resolve_under_root(rel: &Path, bin_dir: &Path) -> Option<PathBuf> {
+    let mut searched = Vec::new();
+    for root in toolchain_roots(bin_dir) {
+        let candidate = root.join(rel);
+        if candidate.try_exists().unwrap_or(false) {
+            return Some(candidate);
+        }
+        searched.push(candidate);
+    }
+    eprintln!("{} not found, searched: {:?}", rel.display(), searched);
+    None
+}
+
+/// Resolves the real suffixed binary, looking next to the wrapper first
+/// (whatever that directory is named) and falling back to the `bin/` of
+/// `ESP_TOOLCHAIN_ROOT` and `PATH` roots.
+fn resolve_tool(name: &str, bin_dir: &Path) -> Option<PathBuf> {
+    let sibling = bin_dir.join(name);
+    if sibling.try_exists().unwrap_or(false) {
+        return Some(sibling);
+    }
+    resolve_under_root(&Path::new("bin").join(name), bin_dir)
+}
+
 /// Checks if a file starts with the specified magic bytes
 fn check_file_magic(path: &Path, magic: &[u8]) -> bool {
     File::open(path)
@@ -122,6 +173,207 @@ fn is_elf_or_static_lib(path: &Path) -> bool {
     path.is_file() && (is_elf_file(path) || is_static_lib(path))
 }
 
+/// `SHT_RISCV_ATTRIBUTES` section type holding `.riscv.attributes`.
+const SHT_RISCV_ATTRIBUTES: u32 = 0x7000_0003;
+
+/// Maps a decoded `Tag_RISCV_arch` string onto the tool suffix we care about,
+/// preferring an explicit `XESPV_VERSIONS` entry over the bare `xesppie`.
+fn arch_string_suffix(arch: &str) -> Option<String> {
+    if let Some(found) = XESPV_VERSIONS.iter().find(|v| arch.contains(*v)) {
+        return Some(found.to_string());
+    }
+    if arch.contains("xesppie") {
+        return Some("xesppie".to_string());
+    }
+    None
+}
+
+/// Decodes a single ULEB128 value, returning it together with the number of
+/// bytes consumed.
+fn read_uleb128(data: &[u8]) -> Option<(u64, usize)> {
+    let mut result: u64 = 0;
+    let mut shift = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        result |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some((result, i + 1));
+        }
+        shift += 7;
+        if shift >= 64 {
+            break;
+        }
+    }
+    None
+}
+
+/// Walks the tag/value pairs of a RISC-V attributes vendor sub-section and
+/// returns the `Tag_RISCV_arch` (tag 5) string if present. Sub-sub-section
+/// headers (`Tag_File`/`Tag_Section`/`Tag_Symbol`) are skipped; unknown tags
+/// follow the EABI convention of ULEB128 values for even tags and
+/// NUL-terminated strings for odd ones.
+fn find_arch_tag(data: &[u8]) -> Option<String> {
+    let mut pos = 0;
+    while pos < data.len() {
+        let (tag, adv) = read_uleb128(&data[pos..])?;
+        pos += adv;
+        match tag {
+            // Sub-sub-section header: a u32 byte-size we can skip over, the
+            // nested attribute tags follow inline.
+            1..=3 => {
+                data.get(pos..pos + 4)?;
+                pos += 4;
+                // Tag_Section/Tag_Symbol also list ULEB128 indices ending in 0.
+                if tag != 1 {
+                    loop {
+                        let (index, adv) = read_uleb128(&data[pos..])?;
+                        pos += adv;
+                        if index == 0 {
+                            break;
+                        }
+                    }
+                }
+            }
+            // Tag_RISCV_arch carries a NUL-terminated architecture string.
+            5 => {
+                let end = data[pos..].iter().position(|&b| b == 0)? + pos;
+                return std::str::from_utf8(&data[pos..end]).ok().map(String::from);
+            }
+            t if t % 2 == 0 => {
+                let (_, adv) = read_uleb128(&data[pos..])?;
+                pos += adv;
+            }
+            _ => {
+                let end = data[pos..].iter().position(|&b| b == 0)? + pos;
+                pos = end + 1;
+            }
+        }
+    }
+    None
+}
+
+/// Parses the body of a `.riscv.attributes` section (leading `'A'` format
+/// version byte, then vendor sub-sections) and returns the arch string of the
+/// `"riscv"` vendor section.
+fn parse_attributes_section(body: &[u8]) -> Option<String> {
+    if body.first() != Some(&b'A') {
+        return None;
+    }
+    let mut pos = 1;
+    while pos + 4 <= body.len() {
+        let len = u32::from_le_bytes(body[pos..pos + 4].try_into().ok()?) as usize;
+        if len < 4 {
+            break;
+        }
+        let sub = body.get(pos..pos + len)?;
+        // Skip the length field, then the NUL-terminated vendor name.
+        let vendor_end = sub[4..].iter().position(|&b| b == 0)? + 4;
+        if &sub[4..vendor_end] == b"riscv" {
+            if let Some(arch) = find_arch_tag(&sub[vendor_end + 1..]) {
+                return Some(arch);
+            }
+        }
+        pos += len;
+    }
+    None
+}
+
+/// Reads the `Tag_RISCV_arch` string from the `.riscv.attributes` section of an
+/// in-memory ELF image, honoring its class (32/64-bit) and endianness.
+fn elf_riscv_arch(data: &[u8]) -> Option<String> {
+    if data.get(..ELF_MAGIC.len()) != Some(&ELF_MAGIC[..]) {
+        return None;
+    }
+    let is_64 = match data.get(4)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let little = match data.get(5)? {
+        1 => true,
+        2 => false,
+        _ => return None,
+    };
+
+    let u16_at = |o: usize| -> Option<u16> {
+        let b = data.get(o..o + 2)?.try_into().ok()?;
+        Some(if little {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    };
+    let u32_at = |o: usize| -> Option<u32> {
+        let b = data.get(o..o + 4)?.try_into().ok()?;
+        Some(if little {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    };
+    let u64_at = |o: usize| -> Option<u64> {
+        let b = data.get(o..o + 8)?.try_into().ok()?;
+        Some(if little {
+            u64::from_le_bytes(b)
+        } else {
+            u64::from_be_bytes(b)
+        })
+    };
+
+    let (shoff, shentsize, shnum) = if is_64 {
+        (u64_at(0x28)? as usize, u16_at(0x3a)? as usize, u16_at(0x3c)? as usize)
+    } else {
+        (u32_at(0x20)? as usize, u16_at(0x2e)? as usize, u16_at(0x30)? as usize)
+    };
+
+    for i in 0..shnum {
+        let sh = shoff + i * shentsize;
+        if u32_at(sh + 4)? != SHT_RISCV_ATTRIBUTES {
+            continue;
+        }
+        let (offset, size) = if is_64 {
+            (u64_at(sh + 24)? as usize, u64_at(sh + 32)? as usize)
+        } else {
+            (u32_at(sh + 16)? as usize, u32_at(sh + 20)? as usize)
+        };
+        return parse_attributes_section(data.get(offset..offset + size)?);
+    }
+    None
+}
+
+/// Iterates the members of a `!<arch>` static library, applying the ELF arch
+/// reader to each ELF member until a known suffix is found.
+fn ar_riscv_arch_suffix(data: &[u8]) -> Option<String> {
+    if data.get(..AR_MAGIC.len()) != Some(AR_MAGIC) {
+        return None;
+    }
+    let mut pos = AR_MAGIC.len();
+    while pos + 60 <= data.len() {
+        let header = &data[pos..pos + 60];
+        let size: usize = std::str::from_utf8(&header[48..58]).ok()?.trim().parse().ok()?;
+        let member = data.get(pos + 60..pos + 60 + size)?;
+        if let Some(arch) = elf_riscv_arch(member).as_deref().and_then(arch_string_suffix) {
+            return Some(arch);
+        }
+        // Members are padded to a 2-byte boundary.
+        pos += 60 + size + (size & 1);
+    }
+    None
+}
+
+/// Determines the tool suffix for a single input file by parsing its
+/// `.riscv.attributes` in-process, without spawning readelf.
+fn file_arch_suffix(path: &Path) -> Option<String> {
+    let mut data = Vec::new();
+    File::open(path).ok()?.read_to_end(&mut data).ok()?;
+    if data.get(..ELF_MAGIC.len()) == Some(&ELF_MAGIC[..]) {
+        return elf_riscv_arch(&data).as_deref().and_then(arch_string_suffix);
+    }
+    if data.get(..AR_MAGIC.len()) == Some(AR_MAGIC) {
+        return ar_riscv_arch_suffix(&data);
+    }
+    None
+}
+
 /// Determines the tool suffix to use based on command line arguments or ELF file analysis
 ///
 /// Priority order:
@@ -136,14 +388,15 @@ fn get_tool_suffix() -> String {
     let mut tool_suffix = String::new();
     let mut march_extension = String::new();
 
-    // Skip the program name
-    let argv: Vec<String> = env::args().skip(1).collect();
+    // Skip the program name. Keep arguments as OsString so a non-UTF-8 source
+    // path, -I dir, or output file does not abort iteration.
+    let argv: Vec<OsString> = env::args_os().skip(1).collect();
 
     /* 1. Iterate and check all "-mespv-spec=" arguments
      * The last one will be applied.
      */
     /* 2. Get suffix from the -march option of as/ld if any of XESPV_VERSIONS is specified */
-    for arg in &argv {
+    for arg in argv.iter().filter_map(|a| a.to_str()) {
         if let Some(value) = arg.strip_prefix(XESPV_ARG_PREFIX) {
             tool_suffix = format!("xespv{}", value);
             esp_debug_trace!("tool_suffix=\"{}\"", tool_suffix);
@@ -171,6 +424,20 @@ fn get_tool_suffix() -> String {
     let stem = wrapper_path.file_stem().expect("file stem").to_string_lossy();
     esp_debug_trace!("stem=\"{}\"", stem);
     if stem.contains("objdump") {
+        /* 3a. Parse .riscv.attributes natively for each ELF or .a input. */
+        for arg in &argv {
+            let path = Path::new(arg);
+            if !is_elf_or_static_lib(path) {
+                continue;
+            }
+            if let Some(found) = file_arch_suffix(path) {
+                esp_debug_trace!("file {} has {}", path.display(), found);
+                return found;
+            }
+        }
+
+        /* 3b. Last-resort fallback: shell out to readelf if the in-process
+         * reader could not determine the architecture from any input. */
         let ext  = wrapper_path.extension().map(|e| e.to_string_lossy());
         let readelf_filename = {
             let base = match stem.rfind('-') {
@@ -270,12 +537,16 @@ fn correct_path(path: PathBuf, _is_short_path: bool) -> PathBuf {
 
 
 fn main() {
-    let mut argv: Vec<String> = std::env::args().collect();
+    let mut argv: Vec<OsString> = std::env::args_os().collect();
     let (wrapper_path, is_short_path) = get_current_exe_path();
     let tool_suffix = get_tool_suffix();
 
-    // Remove all "-mespv-spec=" arguments from argv
-    argv.retain(|arg| !arg.starts_with(XESPV_ARG_PREFIX));
+    // Remove all "-mespv-spec=" arguments from argv. A non-UTF-8 argument can
+    // never be such a flag, so keep it untouched.
+    argv.retain(|arg| {
+        arg.to_str()
+            .is_none_or(|s| !s.starts_with(XESPV_ARG_PREFIX))
+    });
 
     let stem = wrapper_path.file_stem().expect("file stem").to_string_lossy();
     let ext  = wrapper_path.extension().map(|e| e.to_string_lossy());
@@ -287,30 +558,68 @@ fn main() {
         format!("{}-{}", stem, tool_suffix)
     };
 
-    let mut new_exe_path = wrapper_path.clone();
-    new_exe_path.set_file_name(new_name);
+    let bin_dir = wrapper_path
+        .parent()
+        .expect("Executable must be in some directory");
+    let new_exe_path = resolve_tool(&new_name, bin_dir)
+        .unwrap_or_else(|| panic!("Tool {} was not found", new_name));
 
-    argv[0] = correct_path(new_exe_path, is_short_path).display().to_string();
+    argv[0] = correct_path(new_exe_path, is_short_path).into_os_string();
 
     esp_debug_trace!("Execute: {:?}", argv);
     exec(argv);
 }
 
+/// Propagates a finished child's exit status, following the shell convention of
+/// `128 + signal` for a child killed by a signal. Shared by the Windows and the
+/// Unix non-exec path so behavior stays consistent.
+fn finish(status: std::process::ExitStatus) -> ! {
+    use std::process::exit;
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            esp_debug_trace!("Child process killed by signal {}", signal);
+            exit(128 + signal);
+        }
+    }
+    esp_debug_trace!("Child process exited with code {:?}", status.code());
+    exit(status.code().unwrap_or(-1));
+}
+
+/// Spawns the child, waits for it, and forwards its status through `finish`.
+fn spawn_and_wait(argv: &[OsString]) -> ! {
+    let status = Command::new(&argv[0])
+        .args(&argv[1..])
+        .spawn()
+        .expect("Failed to start child process")
+        .wait()
+        .expect("Failed to wait for child process");
+    finish(status);
+}
+
 #[cfg(unix)]
-/// Executes a command on Unix systems by replacing the current process
+/// Executes a command on Unix systems by replacing the current process, unless
+/// `ESP_WRAPPER_NO_EXEC` asks the wrapper to stay alive and spawn+wait instead.
 ///
 /// # Arguments
 /// * `argv` - Command and arguments vector
-fn exec(argv: Vec<String>) {
+fn exec(argv: Vec<OsString>) {
     use std::os::unix::process::CommandExt;
+
+    if env::var_os(NO_EXEC_ENV_NAME).is_some() {
+        esp_debug_trace!("{} set, spawning instead of exec", NO_EXEC_ENV_NAME);
+        spawn_and_wait(&argv);
+    }
+
     let app = &argv[0];
     let args = &argv[1..];
     let err = Command::new(app)
         .args(args)
         .exec(); // exec replaces the current process on Unix
 
-    eprintln!("{} {:?} failed with error({})", app, args, err);
-    unreachable!();
+    eprintln!("{:?} {:?} failed with error({})", app, args, err);
+    std::process::exit(127);
 }
 
 #[cfg(windows)]
@@ -318,21 +627,8 @@ fn exec(argv: Vec<String>) {
 ///
 /// # Arguments
 /// * `argv` - Command and arguments vector
-fn exec(argv: Vec<String>) {
-    use std::process::{exit, ExitStatus};
-
-    let mut child = Command::new(argv.get(0).expect("app in argv[0]"))
-        .args(&argv[1..])
-        .spawn()
-        .expect("Failed to start child process");
-
-    let status: ExitStatus = child.wait().expect("Failed to wait for child process");
-
-    esp_debug_trace!("Child process exited with code {:?}", status.code());
-    match status.code() {
-        Some(c) => exit(c),
-        None => exit(-1),
-    };
+fn exec(argv: Vec<OsString>) {
+    spawn_and_wait(&argv);
 }
 
 #[cfg(all(windows, target_pointer_width = "32"))]